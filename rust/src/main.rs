@@ -1,91 +1,449 @@
-use std::env;
-use std::process;
-use divisors;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{self, BufWriter, Write};
+use std::sync::mpsc;
+use std::thread;
+use clap::{Parser, Subcommand, ValueEnum};
+use num_bigint::BigUint;
+use num_traits::{One, ToPrimitive};
+use rayon::prelude::*;
 
-fn zaremba_tau(n: u64) -> (f64, usize) {
-    let mut divisors = divisors::get_divisors(n);
+/// Prime-factorize `n` by trial division up to `sqrt(n)`, returning the
+/// exponent map as `(prime, exponent)` pairs in ascending prime order.
+/// `n == 1` yields an empty vector.
+fn factorize(n: u64) -> Vec<(u64, u32)> {
+    let mut factors = Vec::new();
+    let mut remaining = n;
+    let mut p = 2_u64;
+    while p * p <= remaining {
+        if remaining.is_multiple_of(p) {
+            let mut exp = 0;
+            while remaining.is_multiple_of(p) {
+                remaining /= p;
+                exp += 1;
+            }
+            factors.push((p, exp));
+        }
+        p += 1;
+    }
+    if remaining > 1 {
+        factors.push((remaining, 1));
+    }
+    factors
+}
 
-    // The divisors crate usually doesn't include 1 or n in the
-    // divisors, except for get_divisors(2) == [2]. But the output is
-    // sorted, so we can add 1 and n after peeking at the start and
-    // end.
-    //
-    // Look at the high end first because that's where we're going to
-    // shove any new values.
-    if divisors.len() == 0 || divisors[divisors.len()-1] != n {
-        divisors.push(n)
+/// Like `factorize`, but for `n` too large to fit in a `u64`. The
+/// residual prime (if any) is still assumed to fit in a `u64` -- finding
+/// the factors of astronomically large semiprimes is out of scope here.
+fn factorize_biguint(n: &BigUint) -> Vec<(u64, u32)> {
+    let mut factors = Vec::new();
+    let mut remaining = n.clone();
+    let mut p = 2_u64;
+    loop {
+        let p_big = BigUint::from(p);
+        if &p_big * &p_big > remaining {
+            break;
+        }
+        if (&remaining % &p_big) == BigUint::from(0_u32) {
+            let mut exp = 0;
+            while (&remaining % &p_big) == BigUint::from(0_u32) {
+                remaining /= &p_big;
+                exp += 1;
+            }
+            factors.push((p, exp));
+        }
+        p += 1;
     }
-    if divisors.len() == 0 || divisors[0] != 1 {
-        divisors.push(1)
+    if remaining > BigUint::one() {
+        let p = remaining
+            .to_u64()
+            .expect("residual prime factor too large to represent as u64");
+        factors.push((p, 1));
     }
-    // It's not sorted anymore, but that's OK.
+    factors
+}
+
+/// Reconstruct `n` from its exponent map, as a `BigUint` so it can be
+/// displayed even when it doesn't fit in any native integer type.
+fn n_from_factors(factors: &[(u64, u32)]) -> BigUint {
+    let mut n = BigUint::one();
+    for &(p, e) in factors {
+        n *= BigUint::from(p).pow(e);
+    }
+    n
+}
+
+/// Compute `z(n) = Σ_{d|n} ln(d)/d` and `tau(n)` directly from `n`'s
+/// exponent map, without ever materializing a divisor's integer value.
+/// Each divisor is an exponent vector; `ln(d) = Σ e_i·ln(p_i)` and
+/// `1/d = Π p_i^{-e_i}` are accumulated in floating point via the same
+/// cartesian expansion used for enumerating divisors, which keeps this
+/// correct for n far beyond what fits in a u64 or u128.
+fn zaremba_tau_from_factors(factors: &[(u64, u32)]) -> (f64, usize) {
+    let mut divisor_logs = vec![0.0_f64]; // ln(d), starting from d = 1
+    let mut divisor_invs = vec![1.0_f64]; // 1/d
+
+    for &(p, e) in factors {
+        let log_p = (p as f64).ln();
+        let inv_p = 1.0 / (p as f64);
+
+        let mut logs = Vec::with_capacity(divisor_logs.len() * (e as usize + 1));
+        let mut invs = Vec::with_capacity(divisor_invs.len() * (e as usize + 1));
+        let mut log_power = 0.0_f64;
+        let mut inv_power = 1.0_f64;
+        for _ in 0..=e {
+            for (&base_log, &base_inv) in divisor_logs.iter().zip(divisor_invs.iter()) {
+                logs.push(base_log + log_power);
+                invs.push(base_inv * inv_power);
+            }
+            log_power += log_p;
+            inv_power *= inv_p;
+        }
+        divisor_logs = logs;
+        divisor_invs = invs;
+    }
+
+    let tau = divisor_logs.len();
 
-    let tau = divisors.len();
-    let mut z = 0_f64;
-    for d in divisors {
-        let df = d as f64;
-        z += df.ln() / df;
+    // Sum the smallest divisors (largest 1/d) first so the running total
+    // accumulates its biggest terms before the long tail of tiny ones.
+    let mut order: Vec<usize> = (0..divisor_invs.len()).collect();
+    order.sort_by(|&a, &b| divisor_invs[b].partial_cmp(&divisor_invs[a]).unwrap());
+
+    let mut z = 0.0_f64;
+    for i in order {
+        z += divisor_logs[i] * divisor_invs[i];
     }
     (z, tau)
 }
 
-fn do_single(n: u64) {
-    let (z, tau) = zaremba_tau(n);
+fn zaremba_tau(n: u64) -> (f64, usize) {
+    zaremba_tau_from_factors(&factorize(n))
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum OutputFormat {
+    Human,
+    Tsv,
+    Csv,
+    Json,
+}
+
+/// Render a float as a JSON number, falling back to `null` for NaN/inf
+/// (e.g. `ratio` when `tau == 1`, since `ln(1) == 0`), neither of which
+/// is valid JSON.
+fn json_float(x: f64) -> String {
+    if x.is_finite() {
+        x.to_string()
+    } else {
+        "null".to_string()
+    }
+}
+
+fn format_row<N: fmt::Display>(format: OutputFormat, n: N, z: f64, tau: usize, ratio: f64) -> String {
+    match format {
+        OutputFormat::Human => format!(
+            "z({n}) = {z}\ttau({n}) = {tau}\tz({n})/ln(tau({n})) = {ratio}",
+            n = n, z = z, tau = tau, ratio = ratio
+        ),
+        OutputFormat::Tsv => format!("{n}\t{z}\t{tau}\t{ratio}"),
+        OutputFormat::Csv => format!("{n},{z},{tau},{ratio}"),
+        OutputFormat::Json => format!(
+            "{{\"n\":\"{n}\",\"z\":{z},\"tau\":{tau},\"ratio\":{ratio}}}",
+            z = json_float(z), ratio = json_float(ratio)
+        ),
+    }
+}
+
+fn format_record_row<N: fmt::Display>(
+    format: OutputFormat,
+    n: N,
+    record: &str,
+    z: f64,
+    tau: usize,
+    ratio: f64,
+) -> String {
+    match format {
+        OutputFormat::Human => format!(
+            "{n}\trecord={record}\tz({n}) = {z}\ttau({n}) = {tau}\tz({n})/ln(tau({n})) = {ratio}",
+            n = n, record = record, z = z, tau = tau, ratio = ratio
+        ),
+        OutputFormat::Tsv => format!("{n}\t{record}\t{z}\t{tau}\t{ratio}"),
+        OutputFormat::Csv => format!("{n},{record},{z},{tau},{ratio}"),
+        OutputFormat::Json => format!(
+            "{{\"n\":\"{n}\",\"record\":\"{record}\",\"z\":{z},\"tau\":{tau},\"ratio\":{ratio}}}",
+            z = json_float(z), ratio = json_float(ratio)
+        ),
+    }
+}
+
+fn do_single(n: &BigUint, format: OutputFormat) {
+    let (z, tau) = zaremba_tau_from_factors(&factorize_biguint(n));
     let ratio = z / (tau as f64).ln();
-    println!(
-        "z({n}) = {z}\ttau({n}) = {tau}\tz({n}/ln(tau({n})) = {ratio}",
-        n = n, z = z, tau = tau, ratio = ratio
-    )
+    println!("{}", format_row(format, n, z, tau, ratio))
+}
+
+/// Tracks the running z/ratio prefix maxima. Must be fed in increasing
+/// order of n. Doesn't do any I/O itself -- callers decide whether and
+/// how to print each row, e.g. to apply a `--start` cutoff or pick an
+/// output format.
+struct RecordState {
+    record_z: f64,
+    record_ratio: f64,
 }
 
-fn do_records(max_n: u64) {
-    let mut record_z = 0.0;
-    let mut record_ratio = 0.0;
-    for n in 1..max_n {
-        let (z, tau) = zaremba_tau(n);
+impl RecordState {
+    fn new() -> Self {
+        RecordState { record_z: 0.0, record_ratio: 0.0 }
+    }
+
+    /// Update the prefix maxima for (z, tau), returning the ratio and,
+    /// if this row sets a z-record, ratio-record, or both, which.
+    fn observe(&mut self, z: f64, tau: usize) -> (f64, Option<&'static str>) {
         let ratio = z / (tau as f64).ln();
 
-        let is_record_z = record_z > 0.0 && z > record_z;
-        let is_record_ratio = record_ratio > 0.0 && ratio > record_ratio;
+        let is_record_z = self.record_z > 0.0 && z > self.record_z;
+        let is_record_ratio = self.record_ratio > 0.0 && ratio > self.record_ratio;
 
         let record_type =
             if is_record_z && is_record_ratio { Some("both")
             } else if is_record_z && !is_record_ratio { Some("z")
             } else if !is_record_z && is_record_ratio { Some("ratio")
             } else { None };
-        if let Some(set_records) = record_type {
-            println!(
-                "{n}\trecord={set_records}\tz({n}) = {z}\ttau({n}) = {tau}\tz({n})/ln(tau({n})) = {ratio}",
-                n=n, set_records=set_records, z=z, tau=tau, ratio=ratio
-            );
+
+        self.record_z = self.record_z.max(z);
+        self.record_ratio = self.record_ratio.max(ratio);
+
+        (ratio, record_type)
+    }
+}
+
+// Chunk size for the parallel sweep: large enough that each rayon task
+// does meaningful work, small enough that the final sequential pass
+// doesn't wait on one straggler chunk before the first record can print.
+const RECORDS_CHUNK_SIZE: u64 = 10_000;
+
+// do_records splits 1..max_n into contiguous chunks and hands them to a
+// rayon worker pool running on its own producer thread; finished chunks
+// are sent over an mpsc channel to a dedicated output thread, which is
+// the only place that touches the record_z/record_ratio prefix maxima
+// and stdout. This keeps a long sweep streaming steady, ordered output
+// instead of blocking on println! in the same loop as the arithmetic.
+//
+// The sweep always starts computing and feeding RecordState from n=1,
+// even when `start` is greater than 1: the record prefix maxima depend
+// on every smaller n, so skipping straight to `start` would seed the
+// state at zero and report spurious record-setters. `start` only gates
+// which rows get printed.
+fn do_records(start: u64, max_n: u64, format: OutputFormat) {
+    let (tx, rx) = mpsc::channel::<(u64, Vec<(u64, f64, usize)>)>();
+    let chunk_starts: Vec<u64> = (1..max_n).step_by(RECORDS_CHUNK_SIZE as usize).collect();
+
+    let producer = thread::spawn(move || {
+        chunk_starts.into_par_iter().for_each_with(tx, |tx, chunk_start| {
+            let end = (chunk_start + RECORDS_CHUNK_SIZE).min(max_n);
+            let chunk: Vec<(u64, f64, usize)> = (chunk_start..end)
+                .map(|n| {
+                    let (z, tau) = zaremba_tau(n);
+                    (n, z, tau)
+                })
+                .collect();
+            tx.send((chunk_start, chunk)).expect("output thread hung up");
+        });
+    });
+
+    let output = thread::spawn(move || {
+        let mut out = BufWriter::new(io::stdout());
+        // Chunks can arrive out of order since the worker pool runs them
+        // concurrently; buffer by starting n until the next contiguous
+        // chunk is ready, then record-scan and flush it.
+        let mut pending: BTreeMap<u64, Vec<(u64, f64, usize)>> = BTreeMap::new();
+        let mut next_start = 1;
+        let mut records = RecordState::new();
+
+        for (chunk_start, chunk) in rx {
+            pending.insert(chunk_start, chunk);
+            while let Some(chunk) = pending.remove(&next_start) {
+                for (n, z, tau) in chunk {
+                    let (ratio, record_type) = records.observe(z, tau);
+                    if n >= start {
+                        if let Some(record) = record_type {
+                            writeln!(out, "{}", format_record_row(format, n, record, z, tau, ratio))
+                                .expect("write to stdout failed");
+                        }
+                    }
+                }
+                out.flush().expect("flush stdout failed");
+                next_start += RECORDS_CHUNK_SIZE;
+            }
         }
+    });
+
+    producer.join().expect("producer thread panicked");
+    output.join().expect("output thread panicked");
+}
 
-        record_z = record_z.max(z);
-        record_ratio = record_ratio.max(ratio);
+fn is_prime(n: u64) -> bool {
+    if n < 2 {
+        return false;
     }
+    if n.is_multiple_of(2) {
+        return n == 2;
+    }
+    let mut p = 3_u64;
+    while p * p <= n {
+        if n.is_multiple_of(p) {
+            return false;
+        }
+        p += 2;
+    }
+    true
 }
 
-fn die_with_usage() {
-    println!("Usage:");
-    println!("  ./zaremba single [n]");
-    println!("  ./zaremba records [max-n]");
-    process::exit(1)
+fn next_prime(after: u64) -> u64 {
+    let mut candidate = after + 1;
+    while !is_prime(candidate) {
+        candidate += 1;
+    }
+    candidate
 }
 
-fn main() {
-    let args: Vec<String> = env::args().collect();
+/// Enumerate candidate record-setters: numbers `2^a1 * 3^a2 * 5^a3 * ...`
+/// with non-increasing exponents `a1 >= a2 >= ... >= 1` (the shape most
+/// record-dense numbers are known to take), below `max_n` (exclusive,
+/// matching `records`). This prunes the search space from O(max_n)
+/// integers to a tiny fraction of it, at the cost of being a heuristic:
+/// an n whose factorization isn't of this shape (e.g. n = 3) can never
+/// be generated here, even if it's a genuine record under `records`.
+/// `max_n` is a `BigUint` (and candidates are returned as exponent maps
+/// rather than integers) so that search isn't bounded by what fits in a
+/// native integer -- only the handful of small primes involved are.
+fn search_candidates(max_n: &BigUint) -> Vec<Vec<(u64, u32)>> {
+    let mut candidates = vec![Vec::new()]; // n = 1 has an empty exponent map
+    let mut primes = vec![2_u64];
+    search_dfs(&mut primes, 0, Vec::new(), BigUint::one(), u32::MAX, max_n, &mut candidates);
+    candidates.retain(|factors| n_from_factors(factors) < *max_n);
+    candidates.sort_by_key(|factors| n_from_factors(factors));
+    candidates
+}
+
+// Depth is the index into the (lazily-extended) ascending prime list;
+// exponents must apply to consecutive primes starting at 2, and each
+// depth's exponent may not exceed the exponent chosen at the previous
+// depth. `current_value` is the product accumulated so far, as a
+// BigUint so arbitrarily deep/high exponents never overflow.
+fn search_dfs(
+    primes: &mut Vec<u64>,
+    depth: usize,
+    current_factors: Vec<(u64, u32)>,
+    current_value: BigUint,
+    max_exp: u32,
+    max_n: &BigUint,
+    candidates: &mut Vec<Vec<(u64, u32)>>,
+) {
+    if depth == primes.len() {
+        primes.push(next_prime(*primes.last().unwrap()));
+    }
+    let p = primes[depth];
+    let p_big = BigUint::from(p);
+
+    let mut value = current_value;
+    for exp in 1..=max_exp {
+        value *= &p_big;
+        if &value >= max_n {
+            break;
+        }
+        let mut factors = current_factors.clone();
+        factors.push((p, exp));
+        candidates.push(factors.clone());
+        search_dfs(primes, depth + 1, factors, value.clone(), exp, max_n, candidates);
+    }
+}
 
-    if args.len() != 3 {
-        println!("Wrong number of arguments, expecting 2.");
-        die_with_usage()
+// Candidates below `start` still have to be fed through RecordState in
+// ascending order, since the record prefix maxima depend on every
+// smaller n; `start` only gates which rows get printed (see do_records).
+fn do_search(start: &BigUint, max_n: &BigUint, format: OutputFormat) {
+    let mut out = BufWriter::new(io::stdout());
+    let mut records = RecordState::new();
+    for factors in search_candidates(max_n) {
+        let n = n_from_factors(&factors);
+        let (z, tau) = zaremba_tau_from_factors(&factors);
+        let (ratio, record_type) = records.observe(z, tau);
+        if &n >= start {
+            if let Some(record) = record_type {
+                writeln!(out, "{}", format_record_row(format, &n, record, z, tau, ratio))
+                    .expect("write to stdout failed");
+            }
+        }
     }
+    out.flush().expect("flush stdout failed");
+}
+
+/// Explore Zaremba's conjecture: z(n) = sum of ln(d)/d over divisors d
+/// of n, and tau(n) = the number of divisors of n.
+#[derive(Parser)]
+#[command(name = "zaremba")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Compute z(n) and tau(n) for a single n.
+    Single {
+        n: BigUint,
+        #[arg(long, value_enum, default_value_t = OutputFormat::Human)]
+        format: OutputFormat,
+    },
+    /// Sweep n in [1, max-n) and report z/tau record-setters at or above
+    /// --start.
+    Records {
+        max_n: u64,
+        /// Only report record-setters at or above this n; records are
+        /// still computed against every n from 1.
+        #[arg(long, default_value_t = 1)]
+        start: u64,
+        /// Worker thread count; defaults to all available cores, and
+        /// can also be set via the RAYON_NUM_THREADS env var.
+        #[arg(long)]
+        threads: Option<usize>,
+        #[arg(long, value_enum, default_value_t = OutputFormat::Human)]
+        format: OutputFormat,
+    },
+    /// Like `records`, but only considers candidates with non-increasing
+    /// prime exponents, which prunes the search space drastically and
+    /// supports n far beyond what fits in a u64. This is a heuristic
+    /// subset of `records`, not an equivalent: n whose factorization
+    /// doesn't start at 2 with consecutive, non-increasing exponents
+    /// (e.g. n = 3) are never generated, so a genuine record at such an
+    /// n is silently missed.
+    Search {
+        max_n: BigUint,
+        /// Only report record-setters at or above this n; records are
+        /// still computed against every candidate from 1.
+        #[arg(long, default_value_t = BigUint::from(1_u32))]
+        start: BigUint,
+        #[arg(long, value_enum, default_value_t = OutputFormat::Human)]
+        format: OutputFormat,
+    },
+}
+
+fn main() {
+    let cli = Cli::parse();
 
-    match args[1].as_str() {
-        "single" => do_single(args[2].parse::<u64>().unwrap()),
-        "records" => do_records(args[2].parse::<u64>().unwrap()),
-        _ => {
-            println!("Did not understand command: {}", args[1]);
-            die_with_usage();
+    match cli.command {
+        Command::Single { n, format } => do_single(&n, format),
+        Command::Records { max_n, start, threads, format } => {
+            if let Some(threads) = threads {
+                rayon::ThreadPoolBuilder::new()
+                    .num_threads(threads)
+                    .build_global()
+                    .unwrap();
+            }
+            do_records(start, max_n, format)
         }
+        Command::Search { max_n, start, format } => do_search(&start, &max_n, format),
     }
 }